@@ -0,0 +1,68 @@
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use once_cell::sync::OnceCell;
+use trust_dns::rr::{Name, Record, RecordType};
+
+use crate::config;
+
+/// Negative answers (NXDOMAIN/NODATA) are cached too, but briefly, so a
+/// misbehaving caller retrying a failing lookup doesn't hammer upstream.
+const NEGATIVE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub enum Answer {
+    Addrs(Vec<IpAddr>),
+    Names(Vec<Name>),
+    NxDomain,
+    NoData,
+}
+
+struct Entry {
+    answer: Answer,
+    expires_at: Instant,
+}
+
+type Key = (Name, RecordType);
+
+static CACHE: OnceCell<Mutex<LruCache<Key, Entry>>> = OnceCell::new();
+
+fn cache() -> &'static Mutex<LruCache<Key, Entry>> {
+    CACHE.get_or_init(|| Mutex::new(LruCache::new(config::config().cache_max_entries)))
+}
+
+/// Returns the cached answer for `(name, record_type)` if one is present
+/// and hasn't expired yet.
+pub fn get(name: &Name, record_type: RecordType) -> Option<Answer> {
+    let mut cache = cache().lock().unwrap();
+    let key = (name.clone(), record_type);
+    match cache.get(&key) {
+        Some(entry) if entry.expires_at > Instant::now() => Some(entry.answer.clone()),
+        Some(_) => {
+            cache.pop(&key);
+            None
+        },
+        None => None,
+    }
+}
+
+/// Caches `answer` for `(name, record_type)`, expiring it after the
+/// minimum TTL observed across `answers` (the CNAME chain included). A
+/// negative (NXDOMAIN/NODATA) answer expires after at most `NEGATIVE_TTL`.
+pub fn insert(name: Name, record_type: RecordType, answer: Answer, answers: &[Record]) {
+    let ttl = match answer {
+        Answer::NxDomain | Answer::NoData => min_ttl(answers).map_or(NEGATIVE_TTL, |ttl| ttl.min(NEGATIVE_TTL)),
+        _ => min_ttl(answers).unwrap_or(NEGATIVE_TTL),
+    };
+    let entry = Entry {
+        answer,
+        expires_at: Instant::now() + ttl,
+    };
+    cache().lock().unwrap().put((name, record_type), entry);
+}
+
+fn min_ttl(answers: &[Record]) -> Option<Duration> {
+    answers.iter().map(|r| Duration::from_secs(u64::from(r.ttl()))).min()
+}
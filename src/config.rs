@@ -0,0 +1,183 @@
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+use rustls::Certificate;
+
+/// Path of the on-disk configuration file, read once per process.
+const CONFIG_PATH: &str = "/etc/dohres.conf";
+
+/// The built-in DigiCert root, used when the config doesn't name any
+/// `ca_cert` entries and doesn't opt into `system_roots`.
+const DEFAULT_CA_CERT: &[u8] = include_bytes!("../DigiCertGlobalRootCA.crt");
+
+/// Mozilla's DoH canary domain: resolving it as NXDOMAIN tells a
+/// DoH-capable browser that the system already does DoH, so it should
+/// not also enable its own independent, application-level DoH.
+const DOH_CANARY_DOMAIN: &str = "use-application-dns.net";
+
+/// SHA-256 digest of the IANA root zone KSK (key tag 20326), published at
+/// https://data.iana.org/root-anchors/root-anchors.xml. This is the
+/// default, and only, trust anchor until an operator configures others.
+const ROOT_TRUST_ANCHOR_DIGEST: &str =
+    "E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D";
+
+/// Resolver back-end configuration: which DoH server to talk to, what
+/// name to present over TLS, and which certificate authorities to trust.
+///
+/// Read from `/etc/dohres.conf` on first use and cached for the life of
+/// the process; a missing or unparsable file falls back to the
+/// Cloudflare defaults the module shipped with originally.
+///
+/// There's intentionally no knob for the DoH URL path (e.g. to talk to a
+/// server not mounted at `/dns-query`): `runtime.rs`'s
+/// `HttpsClientStreamBuilder` has no way to set it, so a `doh_path`
+/// option would be dead configuration. Pointing at a non-default path
+/// requires a different HTTP/DoH transport, not a new field here.
+pub struct Config {
+    pub upstream: SocketAddr,
+    pub sni: String,
+    pub ca_certs: Vec<Certificate>,
+    /// Names that should always resolve as NXDOMAIN, without ever being
+    /// sent upstream. Used to signal opt-out to the DoH canary convention
+    /// (see `DOH_CANARY_DOMAIN`), extensible via `opt_out_domain` lines.
+    pub opt_out_domains: Vec<String>,
+    /// When set, answers are validated against the DNSSEC chain of trust
+    /// rooted at `trust_anchor_digests` before being handed back.
+    pub dnssec: bool,
+    /// SHA-256 digests (uppercase hex) of the DS records that anchor
+    /// trust at the root; `dnssec.rs` walks delegations down from these.
+    pub trust_anchor_digests: Vec<String>,
+    /// Upper bound on how long a single upstream query may take before
+    /// `runtime.rs` gives up and reports `NSS_STATUS_TRYAGAIN`.
+    pub query_timeout: Duration,
+    /// Cap on the number of distinct `(Name, RecordType)` entries
+    /// `cache.rs` holds at once; the least recently used entry is evicted
+    /// once it's exceeded.
+    pub cache_max_entries: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            upstream: SocketAddr::new(IpAddr::from_str("1.1.1.1").unwrap(), 443),
+            sni: String::from("cloudflare-dns.com"),
+            ca_certs: vec![Certificate(Vec::from(DEFAULT_CA_CERT))],
+            opt_out_domains: vec![String::from(DOH_CANARY_DOMAIN)],
+            dnssec: false,
+            trust_anchor_digests: vec![String::from(ROOT_TRUST_ANCHOR_DIGEST)],
+            query_timeout: Duration::from_millis(5000),
+            cache_max_entries: 512,
+        }
+    }
+}
+
+impl Config {
+    fn load() -> Config {
+        let text = match fs::read_to_string(CONFIG_PATH) {
+            Ok(text) => text,
+            Err(_) => return Config::default(),
+        };
+        Config::parse(&text)
+    }
+
+    // Simple `key = value` line format, one setting per line, `#` for
+    // comments. Unrecognized or malformed lines are skipped rather than
+    // failing the whole file, so a typo doesn't take the resolver down.
+    fn parse(text: &str) -> Config {
+        let mut cfg = Config::default();
+        let mut upstream_ip: Option<IpAddr> = None;
+        let mut upstream_port: u16 = cfg.upstream.port();
+        let mut ca_certs: Vec<Certificate> = vec![];
+        let mut use_system_roots = false;
+        let mut opt_out_domains: Vec<String> = vec![];
+        let mut trust_anchor_digests: Vec<String> = vec![];
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => continue,
+            };
+            match key {
+                "upstream" => {
+                    if let Ok(ip) = IpAddr::from_str(value) {
+                        upstream_ip = Some(ip);
+                    }
+                },
+                "port" => {
+                    if let Ok(port) = value.parse() {
+                        upstream_port = port;
+                    }
+                },
+                "sni" => cfg.sni = value.to_string(),
+                "ca_cert" => {
+                    if let Ok(der) = fs::read(value) {
+                        ca_certs.push(Certificate(der));
+                    }
+                },
+                "system_roots" => use_system_roots = value == "true",
+                "opt_out_domain" => opt_out_domains.push(value.to_string()),
+                "dnssec" => cfg.dnssec = value == "true",
+                "trust_anchor_digest" => trust_anchor_digests.push(value.to_uppercase()),
+                "timeout_ms" => {
+                    if let Ok(ms) = value.parse() {
+                        cfg.query_timeout = Duration::from_millis(ms);
+                    }
+                },
+                "cache_max_entries" => {
+                    // Zero would make `LruCache::new` unusable for every
+                    // lookup in the process, so a typo'd `0` is rejected
+                    // the same as an unparsable value.
+                    if let Ok(max @ 1..) = value.parse() {
+                        cfg.cache_max_entries = max;
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        if let Some(ip) = upstream_ip {
+            cfg.upstream = SocketAddr::new(ip, upstream_port);
+        } else {
+            cfg.upstream.set_port(upstream_port);
+        }
+        if !ca_certs.is_empty() {
+            cfg.ca_certs = ca_certs;
+        } else if use_system_roots {
+            cfg.ca_certs = load_system_roots();
+        }
+        if !opt_out_domains.is_empty() {
+            cfg.opt_out_domains.extend(opt_out_domains);
+        }
+        if !trust_anchor_digests.is_empty() {
+            cfg.trust_anchor_digests = trust_anchor_digests;
+        }
+        cfg
+    }
+}
+
+// Loads the platform's native trust store (e.g. /etc/ssl/certs on Linux,
+// Keychain on macOS, the Windows cert store) for `system_roots = true`.
+// Falls back to the built-in default CA rather than leaving the client
+// with zero trusted roots if the platform store can't be read.
+fn load_system_roots() -> Vec<Certificate> {
+    match rustls_native_certs::load_native_certs() {
+        Ok(certs) => certs.into_iter().map(|cert| Certificate(cert.0)).collect(),
+        Err(_) => vec![Certificate(Vec::from(DEFAULT_CA_CERT))],
+    }
+}
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Returns the process-wide configuration, parsing `/etc/dohres.conf` on
+/// first call and caching the result for subsequent lookups.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::load)
+}
@@ -0,0 +1,447 @@
+//! DNSSEC validation: RRSIG/DNSKEY signature checks and NSEC/NSEC3 denial
+//! of existence, chained down from a configured root trust anchor.
+//! Opt-in via `dnssec = true` in the config (see `config.rs`).
+
+use ring::digest;
+use trust_dns::op::{Edns, Message};
+use trust_dns::rr::dnssec::rdata::{DNSKEY, SIG};
+use trust_dns::rr::dnssec::{DigestType, Verifier};
+use trust_dns::rr::rdata::{NSEC, NSEC3};
+use trust_dns::rr::{Name, RData, Record, RecordType};
+use trust_dns::serialize::binary::{BinEncodable, BinEncoder};
+
+use crate::config::Config;
+
+/// Outcome of validating an answer against the DNSSEC chain of trust.
+pub enum Validation {
+    /// RRSIGs over the RRset (or NSEC/NSEC3 denial) checked out against a
+    /// chain rooted at a configured trust anchor.
+    Secure,
+    /// The zone isn't signed; nothing to validate. Passed through
+    /// unauthenticated, same as before this module existed.
+    Insecure,
+    /// Signatures failed to verify, or the delegation chain down from the
+    /// trust anchor doesn't hold. Callers must not trust this answer.
+    Bogus,
+}
+
+/// Sets the EDNS0 DO (DNSSEC OK) bit so the upstream includes RRSIGs, and
+/// NSEC/NSEC3 for denial, alongside the requested RRset.
+pub fn set_do_bit(edns: &mut Edns) {
+    edns.set_dnssec_ok(true);
+}
+
+/// Fetches DNSKEY/DS records needed to walk the delegation chain. The
+/// transport lives in `lib.rs::resolve`, so it's injected here rather
+/// than this module owning a client of its own.
+pub trait ChainFetch {
+    fn fetch(&mut self, name: &Name, record_type: RecordType) -> Option<Message>;
+}
+
+/// Validates `msg` as the answer for `name`/`record_type`, authenticating
+/// either the RRset's signature or, for a non-existent name, its
+/// NSEC/NSEC3 denial of existence.
+pub fn validate<F: ChainFetch>(
+    cfg: &Config,
+    name: &Name,
+    record_type: RecordType,
+    msg: &Message,
+    fetch: &mut F,
+) -> Validation {
+    if !cfg.dnssec {
+        return Validation::Insecure;
+    }
+
+    let rrset: Vec<&Record> = msg.answers().iter().filter(|r| r.rr_type() == record_type).collect();
+    let rrsigs_present = msg
+        .answers()
+        .iter()
+        .any(|r| matches!(r.rdata(), RData::SIG(sig) if sig.type_covered() == record_type));
+
+    if rrset.is_empty() {
+        // No RRset to authenticate a signature over: this is a denial of
+        // existence (NXDOMAIN/NODATA), authenticated via NSEC/NSEC3.
+        return validate_denial(cfg, name, msg, fetch);
+    }
+    if !rrsigs_present {
+        // Unsigned zone: DNSSEC-aware resolvers pass this through as
+        // "insecure" rather than treating it as bogus.
+        return Validation::Insecure;
+    }
+
+    validate_signed_rrset(cfg, msg, record_type, &rrset, fetch)
+}
+
+// Verifies at least one RRSIG covering `record_type` against the RRset it
+// actually signs -- the subset of `rrset` sharing the RRSIG's owner name,
+// not the whole thing, since a response can legitimately carry more than
+// one owner name for the same type (e.g. a CNAME-terminated A/AAAA answer).
+// Shared by `validate`'s direct-answer path and `validate_denial`'s
+// NSEC/NSEC3 proof, both of which reduce to "verify a signed RRset".
+fn validate_signed_rrset<F: ChainFetch>(
+    cfg: &Config,
+    msg: &Message,
+    record_type: RecordType,
+    rrset: &[&Record],
+    fetch: &mut F,
+) -> Validation {
+    let rrsigs: Vec<(&Name, &SIG)> = msg
+        .answers()
+        .iter()
+        .filter_map(|r| match r.rdata() {
+            RData::SIG(sig) if sig.type_covered() == record_type => Some((r.name(), sig)),
+            _ => None,
+        })
+        .collect();
+
+    for (owner, sig) in &rrsigs {
+        let dnskey = match authenticate_zone(cfg, sig.signer_name(), fetch) {
+            Some(dnskey) => dnskey,
+            None => continue,
+        };
+        let covered: Vec<&Record> = rrset.iter().copied().filter(|r| r.name() == *owner).collect();
+        if covered.is_empty() {
+            continue;
+        }
+        let signed_data = rrsig_signed_data(sig, &covered);
+        if dnskey.verify(&signed_data, sig.sig()).is_ok() {
+            return Validation::Secure;
+        }
+    }
+    Validation::Bogus
+}
+
+// Reconstructs the exact bytes an RRSIG covers (RFC 4034 section 3.1.8.1):
+// the RRSIG RDATA up to and excluding the signature, followed by the
+// covered RRset in canonical form (owner name lowercased, records sorted,
+// TTL normalized to the one recorded in the RRSIG itself).
+fn rrsig_signed_data(sig: &SIG, rrset: &[&Record]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    {
+        let mut encoder = BinEncoder::new(&mut buf);
+        let _ = sig.emit(&mut encoder);
+    }
+    let mut records: Vec<&Record> = rrset.to_vec();
+    records.sort_by(|a, b| a.rdata().cmp(b.rdata()));
+    for record in records {
+        let mut owner = record.name().clone();
+        owner.set_fqdn(true);
+        owner = owner.to_lowercase();
+        let mut encoder = BinEncoder::new(&mut buf);
+        let _ = owner.emit(&mut encoder);
+        let _ = record.rr_type().emit(&mut encoder);
+        let _ = record.dns_class().emit(&mut encoder);
+        let _ = encoder.emit_u32(sig.original_ttl());
+        let _ = record.rdata().emit(&mut encoder);
+    }
+    buf
+}
+
+/// Authenticates `zone`'s DNSKEY RRset by walking the DS -> DNSKEY
+/// delegation chain down from the configured root trust anchor, and
+/// returns the DNSKEY that should sign records in `zone`.
+fn authenticate_zone<F: ChainFetch>(cfg: &Config, zone: &Name, fetch: &mut F) -> Option<DNSKEY> {
+    let chain = delegation_chain(zone);
+    // Digests actually published by the parent's DS record, paired with
+    // the hash algorithm each one was computed with -- a zone whose
+    // parent only publishes a non-SHA-256 DS must still authenticate.
+    let mut expected_ds_digests: Option<Vec<(DigestType, String)>> = None;
+
+    for (depth, link) in chain.iter().enumerate() {
+        let dnskey_msg = fetch.fetch(link, RecordType::DNSKEY)?;
+        let dnskeys: Vec<&DNSKEY> = dnskey_msg
+            .answers()
+            .iter()
+            .filter_map(|r| match r.rdata() {
+                RData::DNSKEY(k) => Some(k),
+                _ => None,
+            })
+            .collect();
+        if dnskeys.is_empty() {
+            return None;
+        }
+
+        let matching = if depth == 0 {
+            // Root: authenticated directly against the configured anchor
+            // digests, since there's no parent to delegate from. The
+            // anchor digests are documented (see `ROOT_TRUST_ANCHOR_DIGEST`)
+            // as SHA-256, so that's the only algorithm tried here.
+            dnskeys.iter().find(|k| {
+                let digest = ds_digest_hex(link, k, DigestType::SHA256);
+                cfg.trust_anchor_digests.iter().any(|d| Some(d) == digest.as_ref())
+            })
+        } else {
+            let expected = expected_ds_digests.as_ref()?;
+            dnskeys.iter().find(|k| {
+                expected.iter().any(|(digest_type, d)| ds_digest_hex(link, k, *digest_type).as_ref() == Some(d))
+            })
+        };
+        let dnskey = match matching {
+            Some(k) => *k,
+            None => return None,
+        };
+
+        if link == zone {
+            return Some(dnskey.clone());
+        }
+
+        // Descend one label: fetch the DS for the next link down and
+        // remember its digests (and the algorithm each used) as what
+        // that zone's DNSKEY must match.
+        let next = &chain[depth + 1];
+        let ds_msg = fetch.fetch(next, RecordType::DS)?;
+        let digests: Vec<(DigestType, String)> = ds_msg
+            .answers()
+            .iter()
+            .filter_map(|r| match r.rdata() {
+                RData::DS(ds) => Some((ds.digest_type(), hex_encode(ds.digest()))),
+                _ => None,
+            })
+            .collect();
+        if digests.is_empty() {
+            return None;
+        }
+        expected_ds_digests = Some(digests);
+    }
+    None
+}
+
+// Computes the DS digest for `dnskey` as owned by `owner`, per RFC 4034
+// section 5.1.4: digest(owner name in canonical wire form || DNSKEY
+// RDATA), using whichever hash `digest_type` names -- a parent zone is
+// free to publish a DS with any supported algorithm, not just SHA-256.
+// Returns `None` for a digest type this resolver doesn't implement,
+// rather than silently hashing with the wrong algorithm.
+fn ds_digest_hex(owner: &Name, dnskey: &DNSKEY, digest_type: DigestType) -> Option<String> {
+    let algorithm = match digest_type {
+        DigestType::SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        DigestType::SHA256 => &digest::SHA256,
+        DigestType::SHA384 => &digest::SHA384,
+        _ => return None,
+    };
+    let mut buf = Vec::new();
+    let mut encoder = BinEncoder::new(&mut buf);
+    owner.to_lowercase().emit(&mut encoder).ok()?;
+    dnskey.emit(&mut encoder).ok()?;
+    drop(encoder);
+    Some(hex_encode(digest::digest(algorithm, &buf).as_ref()))
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// The root-to-leaf sequence of names whose DNSKEYs must each be
+/// authenticated, e.g. `www.example.com.` -> `[., com., example.com.,
+/// www.example.com.]`.
+fn delegation_chain(name: &Name) -> Vec<Name> {
+    let mut labels: Vec<Name> = name.iter().collect::<Vec<_>>().into_iter().rev().fold(vec![Name::root()], |mut acc, label| {
+        let parent = acc.last().unwrap().clone();
+        acc.push(parent.prepend_label(label.into()));
+        acc
+    });
+    labels.dedup();
+    labels
+}
+
+/// Authenticates a denial of existence using the NSEC/NSEC3 records in
+/// `msg`: the queried name (or its NSEC3 hash) must fall strictly
+/// between two consecutive owner names/hashes, honoring the NSEC3
+/// opt-out flag for insecure delegations.
+fn validate_denial<F: ChainFetch>(cfg: &Config, name: &Name, msg: &Message, fetch: &mut F) -> Validation {
+    let nsec3_records: Vec<&Record> = msg.answers().iter().filter(|r| r.rr_type() == RecordType::NSEC3).collect();
+    if !nsec3_records.is_empty() {
+        let nsec3s: Vec<&NSEC3> = nsec3_records
+            .iter()
+            .filter_map(|r| match r.rdata() {
+                RData::NSEC3(n) => Some(n),
+                _ => None,
+            })
+            .collect();
+        let hashed = nsec3s[0].hash_name(name);
+        let covering: Vec<&&NSEC3> = nsec3s.iter().filter(|n| n.covers(&hashed)).collect();
+        if covering.is_empty() {
+            return Validation::Bogus;
+        }
+        // The NSEC3 RRset itself must carry a valid RRSIG chaining back to
+        // a trusted DNSKEY -- otherwise a forged or replayed NSEC3 from an
+        // unrelated name in the same zone would pass the `covers` check
+        // above without ever being authenticated.
+        let validation = validate_signed_rrset(cfg, msg, RecordType::NSEC3, &nsec3_records, fetch);
+        // An opt-out NSEC3 only proves "this range may contain insecure
+        // delegations", not that the name is absent, so an authenticated
+        // opt-out proof is reported as insecure rather than secure.
+        if let Validation::Secure = validation {
+            if covering.iter().any(|n| n.opt_out()) {
+                return Validation::Insecure;
+            }
+        }
+        return validation;
+    }
+
+    let nsec_records: Vec<&Record> = msg.answers().iter().filter(|r| r.rr_type() == RecordType::NSEC).collect();
+    if nsec_records.is_empty() {
+        if !msg.answers().iter().any(|r| matches!(r.rdata(), RData::SIG(_))) {
+            // Unsigned zone: passed through as insecure, the same as
+            // `validate` does for an unsigned RRset.
+            return Validation::Insecure;
+        }
+        // Signed zone with no denial proof offered: can't authenticate.
+        return Validation::Bogus;
+    }
+    let nsecs: Vec<&NSEC> = nsec_records
+        .iter()
+        .filter_map(|r| match r.rdata() {
+            RData::NSEC(n) => Some(n),
+            _ => None,
+        })
+        .collect();
+    if !nsecs.iter().any(|n| n.covers(name)) {
+        return Validation::Bogus;
+    }
+    validate_signed_rrset(cfg, msg, RecordType::NSEC, &nsec_records, fetch)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    use trust_dns::rr::dnssec::Algorithm;
+    use trust_dns::rr::rdata::nsec3::Nsec3HashAlgorithm;
+    use trust_dns::rr::DNSClass;
+
+    use super::*;
+
+    fn record(name: &Name, ttl: u32, rdata: RData) -> Record {
+        let mut r = Record::new();
+        r.set_name(name.clone());
+        r.set_rr_type(rdata.to_record_type());
+        r.set_dns_class(DNSClass::IN);
+        r.set_ttl(ttl);
+        r.set_rdata(rdata);
+        r
+    }
+
+    struct PanicFetch;
+
+    impl ChainFetch for PanicFetch {
+        fn fetch(&mut self, _name: &Name, _record_type: RecordType) -> Option<Message> {
+            panic!("authenticate_zone should never be reached once the covers() check has failed");
+        }
+    }
+
+    struct DnskeyFetch<'a>(&'a Message);
+
+    impl<'a> ChainFetch for DnskeyFetch<'a> {
+        fn fetch(&mut self, _name: &Name, record_type: RecordType) -> Option<Message> {
+            match record_type {
+                RecordType::DNSKEY => Some(self.0.clone()),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn rrsig_signed_data_sorts_by_rdata_and_uses_the_rrsigs_original_ttl() {
+        let name = Name::from_str("example.com.").unwrap();
+        // `original_ttl` deliberately differs from the records' own TTL: the
+        // canonical form must use the RRSIG's TTL, not the wire TTL.
+        let sig = SIG::new(RecordType::A, Algorithm::RSASHA256, 2, 999, 0, 0, 0, name.clone(), vec![]);
+        let hi = record(&name, 300, RData::A(Ipv4Addr::new(2, 2, 2, 2)));
+        let lo = record(&name, 300, RData::A(Ipv4Addr::new(1, 1, 1, 1)));
+
+        // Passed in reverse-sorted order; the function must re-sort by rdata.
+        let signed = rrsig_signed_data(&sig, &[&hi, &lo]);
+
+        let mut expected = Vec::new();
+        {
+            let mut encoder = BinEncoder::new(&mut expected);
+            let _ = sig.emit(&mut encoder);
+        }
+        for r in [&lo, &hi] {
+            let mut owner = name.clone();
+            owner.set_fqdn(true);
+            owner = owner.to_lowercase();
+            let mut encoder = BinEncoder::new(&mut expected);
+            let _ = owner.emit(&mut encoder);
+            let _ = RecordType::A.emit(&mut encoder);
+            let _ = DNSClass::IN.emit(&mut encoder);
+            let _ = encoder.emit_u32(999);
+            let _ = r.rdata().emit(&mut encoder);
+        }
+        assert_eq!(signed, expected);
+    }
+
+    #[test]
+    fn ds_digest_hex_dispatches_on_digest_type_and_rejects_unsupported_ones() {
+        let owner = Name::from_str("example.com.").unwrap();
+        let dnskey = DNSKEY::new(true, true, false, Algorithm::RSASHA256, vec![1, 2, 3, 4]);
+
+        let sha1 = ds_digest_hex(&owner, &dnskey, DigestType::SHA1).expect("SHA-1 is supported");
+        let sha256 = ds_digest_hex(&owner, &dnskey, DigestType::SHA256).expect("SHA-256 is supported");
+        let sha384 = ds_digest_hex(&owner, &dnskey, DigestType::SHA384).expect("SHA-384 is supported");
+        assert_eq!(sha1.len(), 40); // 20-byte digest, hex-encoded
+        assert_eq!(sha256.len(), 64); // 32-byte digest, hex-encoded
+        assert_eq!(sha384.len(), 96); // 48-byte digest, hex-encoded
+        assert_ne!(sha1, sha256);
+        assert_ne!(sha256, sha384);
+
+        // GOST R 34.11-94 (digest type 3) isn't implemented; fail closed
+        // rather than silently hashing with the wrong algorithm.
+        assert!(ds_digest_hex(&owner, &dnskey, DigestType::GOSTR34_11_94).is_none());
+    }
+
+    #[test]
+    fn validate_signed_rrset_rejects_an_rrsig_for_a_different_owner_name() {
+        let root = Name::root();
+        let dnskey = DNSKEY::new(true, true, false, Algorithm::RSASHA256, vec![9, 9, 9]);
+        let digest = ds_digest_hex(&root, &dnskey, DigestType::SHA256).unwrap();
+
+        let mut cfg = Config::default();
+        cfg.dnssec = true;
+        cfg.trust_anchor_digests = vec![digest];
+
+        let mut dnskey_msg = Message::new();
+        dnskey_msg.add_answer(record(&root, 3600, RData::DNSKEY(dnskey)));
+
+        let answer_name = Name::from_str("a.example.com.").unwrap();
+        let other_name = Name::from_str("b.example.com.").unwrap();
+        let a_record = record(&answer_name, 300, RData::A(Ipv4Addr::new(10, 0, 0, 1)));
+        let sig = SIG::new(RecordType::A, Algorithm::RSASHA256, 2, 300, 0, 0, 0, root.clone(), vec![]);
+        // The RRSIG's owner is `other_name`, not the RRset's actual owner --
+        // it must never be treated as covering `a_record`.
+        let sig_record = record(&other_name, 300, RData::SIG(sig));
+
+        let mut msg = Message::new();
+        msg.add_answer(a_record.clone());
+        msg.add_answer(sig_record);
+
+        let rrset = vec![&a_record];
+        let mut fetch = DnskeyFetch(&dnskey_msg);
+        match validate_signed_rrset(&cfg, &msg, RecordType::A, &rrset, &mut fetch) {
+            Validation::Bogus => {},
+            _ => panic!("a wrong-owner RRSIG must not validate the RRset"),
+        }
+    }
+
+    #[test]
+    fn validate_denial_rejects_an_nsec3_that_does_not_cover_the_name() {
+        let name = Name::from_str("nonexistent.example.com.").unwrap();
+        // An empty next-hashed-owner bound can't cover any real hash.
+        let nsec3 = NSEC3::new(Nsec3HashAlgorithm::SHA1, false, 0, vec![], vec![], vec![RecordType::A]);
+        let hashed = nsec3.hash_name(&name);
+        assert!(!nsec3.covers(&hashed));
+
+        let mut cfg = Config::default();
+        cfg.dnssec = true;
+        let mut msg = Message::new();
+        msg.add_answer(record(&name, 300, RData::NSEC3(nsec3)));
+
+        let mut fetch = PanicFetch;
+        match validate_denial(&cfg, &name, &msg, &mut fetch) {
+            Validation::Bogus => {},
+            _ => panic!("a non-covering NSEC3 must not authenticate a denial of existence"),
+        }
+    }
+}
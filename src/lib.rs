@@ -1,21 +1,34 @@
 use std::collections::HashSet;
 use std::ffi::CStr;
 use std::mem;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::ptr;
 use std::str::FromStr;
 
-use libc::{self, c_char, c_int, hostent, int32_t, size_t, uint32_t};
-use rustls::Certificate;
-use trust_dns::client::{Client, SyncClient};
-use trust_dns::https::HttpsClientConnection;
-use trust_dns::op::{DnsResponse, ResponseCode};
-use trust_dns::rr::{DNSClass, Name, RData, RecordType};
+use libc::{self, c_char, c_int, c_void, hostent, int32_t, size_t, socklen_t, uint32_t};
+use trust_dns::op::{DnsResponse, Message, ResponseCode};
+use trust_dns::rr::{Name, RData, RecordType};
+
+mod cache;
+mod config;
+mod dnssec;
+mod reverse;
+mod runtime;
+
+/// Fetches auxiliary DNSKEY/DS records needed to validate a DNSSEC chain,
+/// by issuing further queries through the same upstream as `resolve()`.
+struct UpstreamFetch;
+
+impl dnssec::ChainFetch for UpstreamFetch {
+    fn fetch(&mut self, name: &Name, record_type: RecordType) -> Option<Message> {
+        resolve(name, record_type).ok()?.messages().nth(0).cloned()
+    }
+}
 
 pub const NSS_STATUS_TRYAGAIN: c_int = -2;
-pub const NSS_STATUS_UNAVAIL: c_int  = -1; 
-pub const NSS_STATUS_NOTFOUND: c_int = 0; 
-pub const NSS_STATUS_SUCCESS: c_int  = 1; 
+pub const NSS_STATUS_UNAVAIL: c_int  = -1;
+pub const NSS_STATUS_NOTFOUND: c_int = 0;
+pub const NSS_STATUS_SUCCESS: c_int  = 1;
 
 pub const HOST_NOT_FOUND: c_int = 1;
 pub const TRY_AGAIN: c_int      = 2;
@@ -55,11 +68,11 @@ pub extern "C" fn _nss_doh_gethostbyname2_r(
     errnop: *mut c_int,
     h_errnop: *mut c_int,
 ) -> c_int {
-    if af == libc::AF_INET6 {
+    if af != libc::AF_INET && af != libc::AF_INET6 {
         return NSS_STATUS_NOTFOUND;
     }
     let r_name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
-    let addrs = match retrieve_addrs(r_name.as_ref(), errnop, h_errnop) {
+    let addrs = match retrieve_addrs(r_name.as_ref(), af, errnop, h_errnop) {
         (Some(addrs), _) => addrs,
         (None, status) => return status,
     };
@@ -76,8 +89,9 @@ pub extern "C" fn _nss_doh_gethostbyname2_r(
     // addresses follow h_addr_list
     // h_name follows addresses
     //
+    let addr_len = if af == libc::AF_INET6 { 16 } else { 4 };
     let ptr_size = mem::size_of::<*const c_char>();
-    let data_size = addrs.len() * (ptr_size + 4) + ptr_size + r_name.len() + 1;
+    let data_size = addrs.len() * (ptr_size + addr_len) + ptr_size + r_name.len() + 1;
     if data_size > buflen {
         unsafe {
             ptr::write(errnop, libc::ERANGE);
@@ -88,12 +102,13 @@ pub extern "C" fn _nss_doh_gethostbyname2_r(
     let mut addr_data_offset = (addrs.len() + 1) * ptr_size;
     for (ix, addr) in addrs.iter().enumerate() {
         unsafe {
-            ptr::copy(&addr.octets() as *const u8, buf.offset(addr_data_offset as isize) as *mut u8, 4);
+            let octets = ip_octets(addr);
+            ptr::copy(octets.as_ptr(), buf.offset(addr_data_offset as isize) as *mut u8, addr_len);
             let addr_ptr = buf.offset(addr_data_offset as isize) as *const c_char;
             let addr_ptr_slot = (buf as *mut *const c_char).offset(ix as isize);
             ptr::write(addr_ptr_slot, addr_ptr);
         }
-        addr_data_offset += 4;
+        addr_data_offset += addr_len;
     }
     unsafe {
         let null_slot = (buf as *mut *const c_char).offset(addrs.len() as isize);
@@ -102,6 +117,8 @@ pub extern "C" fn _nss_doh_gethostbyname2_r(
         (*result_buf).h_addr_list = buf as *mut *mut c_char;
         (*result_buf).h_aliases = null_slot as *mut *mut c_char;
         (*result_buf).h_name = buf.offset(addr_data_offset as isize) as *mut c_char;
+        (*result_buf).h_addrtype = af;
+        (*result_buf).h_length = addr_len as c_int;
         ptr::write(h_errnop, NETDB_SUCCESS);
     }
     NSS_STATUS_SUCCESS
@@ -117,14 +134,23 @@ pub extern "C" fn _nss_doh_gethostbyname4_r(
     h_errnop: *mut c_int,
     _ttlp: *mut int32_t,
 ) -> c_int {
-    if unsafe { (**pat).family } == libc::AF_INET6 {
-        return NSS_STATUS_NOTFOUND;
-    }
     let r_name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
-    let addrs = match retrieve_addrs(r_name.as_ref(), errnop, h_errnop) {
-        (Some(addrs), _) => addrs,
-        (None, status) => return status,
-    };
+    // gethostbyname4_r is family-agnostic: chain both A and AAAA results
+    // together, fetching whichever aren't already cached in one round trip.
+    let ((v4, v4_status), (v6, v6_status)) = retrieve_addrs_both(r_name.as_ref(), errnop, h_errnop);
+    let mut addrs: Vec<IpAddr> = vec![];
+    addrs.extend(v4.unwrap_or_default());
+    addrs.extend(v6.unwrap_or_default());
+    if addrs.is_empty() {
+        // Prefer the more specific status; NOTFOUND only if both families agree there's nothing.
+        return if v4_status == NSS_STATUS_TRYAGAIN || v6_status == NSS_STATUS_TRYAGAIN {
+            NSS_STATUS_TRYAGAIN
+        } else if v4_status == NSS_STATUS_NOTFOUND {
+            v6_status
+        } else {
+            v4_status
+        };
+    }
 
     //
     // How to pack everything into buf:
@@ -152,8 +178,7 @@ pub extern "C" fn _nss_doh_gethostbyname4_r(
     let mut gaih_array_offset = 0;
     for (ix, addr) in addrs.iter().enumerate() {
         unsafe {
-            let addr_array: [uint32_t; 4] = [0; 4];
-            ptr::copy(&addr.octets() as *const u8, &addr_array as *const u32 as *mut u8, 4);
+            let (addr_array, family) = gaih_addr_and_family(addr);
             let next_ptr = if ix < addrs.len() - 1 {
                 buf.offset((ix * gaih_size) as isize) as *mut gaih_addrtuple
             } else {
@@ -168,7 +193,7 @@ pub extern "C" fn _nss_doh_gethostbyname4_r(
                 next: next_ptr,
                 name: name_ptr,
                 addr: addr_array,
-                family: libc::AF_INET,
+                family,
                 scopeid: 0,
             };
             if ix == 0 {
@@ -187,11 +212,247 @@ pub extern "C" fn _nss_doh_gethostbyname4_r(
     NSS_STATUS_SUCCESS
 }
 
-fn retrieve_addrs(name: &str, errnop: *mut c_int, h_errnop: *mut c_int) -> (Option<Vec<Ipv4Addr>>, c_int) {
+// Copies an IpAddr's network-order bytes out into a stack buffer long enough for either family.
+fn ip_octets(addr: &IpAddr) -> [u8; 16] {
+    let mut buf = [0u8; 16];
+    match addr {
+        IpAddr::V4(v4) => buf[..4].copy_from_slice(&v4.octets()),
+        IpAddr::V6(v6) => buf.copy_from_slice(&v6.octets()),
+    }
+    buf
+}
+
+// Packs an IpAddr into gaih_addrtuple's fixed-size address field and reports its family.
+fn gaih_addr_and_family(addr: &IpAddr) -> ([uint32_t; 4], c_int) {
+    let mut arr = [0u32; 4];
+    let octets = ip_octets(addr);
+    unsafe {
+        ptr::copy(octets.as_ptr(), &mut arr as *mut _ as *mut u8, 16);
+    }
+    let family = match addr {
+        IpAddr::V4(_) => libc::AF_INET,
+        IpAddr::V6(_) => libc::AF_INET6,
+    };
+    (arr, family)
+}
+
+#[no_mangle]
+pub extern "C" fn _nss_doh_gethostbyaddr_r(
+    addr: *const c_void,
+    len: socklen_t,
+    af: c_int,
+    result_buf: *mut hostent,
+    buf: *mut c_char,
+    buflen: size_t,
+    errnop: *mut c_int,
+    h_errnop: *mut c_int,
+) -> c_int {
+    let addr_len: usize = match af {
+        libc::AF_INET => 4,
+        libc::AF_INET6 => 16,
+        _ => {
+            unsafe {
+                ptr::write(errnop, libc::EINVAL);
+                ptr::write(h_errnop, NO_RECOVERY);
+            }
+            return NSS_STATUS_UNAVAIL;
+        },
+    };
+    if len as usize != addr_len {
+        unsafe {
+            ptr::write(errnop, libc::EINVAL);
+            ptr::write(h_errnop, NO_RECOVERY);
+        }
+        return NSS_STATUS_UNAVAIL;
+    }
+    let mut octets = [0u8; 16];
+    unsafe {
+        ptr::copy(addr as *const u8, octets.as_mut_ptr(), addr_len);
+    }
+    let ip = if af == libc::AF_INET6 {
+        let mut v6 = [0u8; 16];
+        v6.copy_from_slice(&octets[..16]);
+        IpAddr::V6(Ipv6Addr::from(v6))
+    } else {
+        IpAddr::V4(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+    };
+
+    let query_name = reverse::arpa_name(&ip);
+    let names = match retrieve_ptr(&query_name, errnop, h_errnop) {
+        (Some(names), _) => names,
+        (None, status) => return status,
+    };
+
+    //
+    // How to pack everything into buf:
+    //
+    // +-----+------+------+----+-----+-+-+-+-+--+----------+
+    // | &a0 | NULL | &n1..| NULL | addr |n|a|m|e|\0| alias1\0 ...
+    // +-----+------+------+----+-----+-+-+-+-+--+----------+
+    //
+    // h_addr_list points to &a0, the single queried address
+    // h_aliases points to any PTR records beyond the first (&n1..)
+    // h_name points to the first PTR record's name, which follows addr
+    //
+    let ptr_size = mem::size_of::<*const c_char>();
+    let name_strs: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+    let alias_count = name_strs.len() - 1;
+    let strings_size: usize = name_strs.iter().map(|s| s.len() + 1).sum();
+    let addr_list_size = 2 * ptr_size;
+    let aliases_list_size = (alias_count + 1) * ptr_size;
+    let data_size = addr_list_size + aliases_list_size + addr_len + strings_size;
+    if data_size > buflen {
+        unsafe {
+            ptr::write(errnop, libc::ERANGE);
+            ptr::write(h_errnop, TRY_AGAIN);
+        }
+        return NSS_STATUS_TRYAGAIN;
+    }
+
+    let addr_data_offset = addr_list_size + aliases_list_size;
+    let mut str_offset = addr_data_offset + addr_len;
+    unsafe {
+        ptr::copy(octets.as_ptr(), buf.offset(addr_data_offset as isize) as *mut u8, addr_len);
+        let addr_list = buf as *mut *const c_char;
+        ptr::write(addr_list, buf.offset(addr_data_offset as isize) as *const c_char);
+        ptr::write(addr_list.offset(1), ptr::null());
+
+        let h_name = &name_strs[0];
+        ptr::copy(h_name.as_ptr(), buf.offset(str_offset as isize) as *mut u8, h_name.len());
+        ptr::write(buf.offset((str_offset + h_name.len()) as isize), 0);
+        (*result_buf).h_name = buf.offset(str_offset as isize) as *mut c_char;
+        str_offset += h_name.len() + 1;
+
+        let aliases_array = buf.offset(addr_list_size as isize) as *mut *const c_char;
+        for (ix, alias) in name_strs[1..].iter().enumerate() {
+            ptr::copy(alias.as_ptr(), buf.offset(str_offset as isize) as *mut u8, alias.len());
+            ptr::write(buf.offset((str_offset + alias.len()) as isize), 0);
+            ptr::write(aliases_array.offset(ix as isize), buf.offset(str_offset as isize) as *const c_char);
+            str_offset += alias.len() + 1;
+        }
+        ptr::write(aliases_array.offset(alias_count as isize), ptr::null());
+
+        (*result_buf).h_addrtype = af;
+        (*result_buf).h_length = addr_len as c_int;
+        (*result_buf).h_addr_list = addr_list as *mut *mut c_char;
+        (*result_buf).h_aliases = aliases_array as *mut *mut c_char;
+        ptr::write(h_errnop, NETDB_SUCCESS);
+    }
+    NSS_STATUS_SUCCESS
+}
+
+fn retrieve_ptr(query_name: &Name, errnop: *mut c_int, h_errnop: *mut c_int) -> (Option<Vec<Name>>, c_int) {
+    unsafe {
+        ptr::write(errnop, libc::ENOENT);
+        ptr::write(h_errnop, NETDB_INTERNAL);
+    }
+    if let Some(answer) = cache::get(query_name, RecordType::PTR) {
+        return match answer {
+            cache::Answer::Names(names) => {
+                unsafe {
+                    ptr::write(h_errnop, NETDB_SUCCESS);
+                }
+                (Some(names), NSS_STATUS_SUCCESS)
+            },
+            cache::Answer::NxDomain => {
+                unsafe {
+                    ptr::write(h_errnop, HOST_NOT_FOUND);
+                }
+                (None, NSS_STATUS_NOTFOUND)
+            },
+            cache::Answer::NoData | cache::Answer::Addrs(_) => {
+                unsafe {
+                    ptr::write(h_errnop, NO_DATA);
+                }
+                (None, NSS_STATUS_NOTFOUND)
+            },
+        };
+    }
+    let resp = match resolve(query_name, RecordType::PTR) {
+        Ok(resp) => resp,
+        Err(runtime::QueryError::Timeout) => {
+            unsafe {
+                ptr::write(errnop, libc::ETIMEDOUT);
+                ptr::write(h_errnop, TRY_AGAIN);
+            }
+            return (None, NSS_STATUS_TRYAGAIN);
+        },
+        Err(runtime::QueryError::Upstream(_)) => return (None, NSS_STATUS_UNAVAIL),
+    };
+    let msg = match resp.messages().nth(0) {
+        Some(msg) => msg,
+        None => return (None, NSS_STATUS_UNAVAIL),
+    };
+    let ans = msg.answers();
+    unsafe {
+        ptr::write(errnop, 0);
+        ptr::write(h_errnop, NO_DATA);
+    }
+    let is_nxdomain = match msg.response_code() {
+        ResponseCode::NoError => false,
+        ResponseCode::NXDomain => true,
+        ResponseCode::ServFail => {
+            unsafe {
+                ptr::write(h_errnop, TRY_AGAIN);
+            }
+            return (None, NSS_STATUS_TRYAGAIN);
+        },
+        _ => {
+            unsafe {
+                ptr::write(h_errnop, NO_RECOVERY);
+            }
+            return (None, NSS_STATUS_UNAVAIL);
+        },
+    };
+    let mut fetch = UpstreamFetch;
+    if let dnssec::Validation::Bogus = dnssec::validate(config::config(), query_name, RecordType::PTR, msg, &mut fetch) {
+        unsafe {
+            ptr::write(h_errnop, NO_RECOVERY);
+        }
+        return (None, NSS_STATUS_UNAVAIL);
+    }
+    if is_nxdomain {
+        unsafe {
+            ptr::write(h_errnop, HOST_NOT_FOUND);
+        }
+        cache::insert(query_name.clone(), RecordType::PTR, cache::Answer::NxDomain, ans);
+        return (None, NSS_STATUS_NOTFOUND);
+    }
+    let names: Vec<Name> = ans
+        .iter()
+        .filter(|r| r.name() == query_name && r.rr_type() == RecordType::PTR)
+        .filter_map(|r| match r.rdata() {
+            RData::PTR(ref name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    if names.is_empty() {
+        cache::insert(query_name.clone(), RecordType::PTR, cache::Answer::NoData, ans);
+        return (None, NSS_STATUS_NOTFOUND);
+    }
+    cache::insert(query_name.clone(), RecordType::PTR, cache::Answer::Names(names.clone()), ans);
+    (Some(names), NSS_STATUS_SUCCESS)
+}
+
+fn retrieve_addrs(name: &str, family: c_int, errnop: *mut c_int, h_errnop: *mut c_int) -> (Option<Vec<IpAddr>>, c_int) {
     unsafe {
         ptr::write(errnop, libc::ENOENT);
         ptr::write(h_errnop, NETDB_INTERNAL);
     }
+    let record_type = match family {
+        libc::AF_INET => RecordType::A,
+        libc::AF_INET6 => RecordType::AAAA,
+        _ => return (None, NSS_STATUS_UNAVAIL),
+    };
+    // Opt-out signaling domains (e.g. the Mozilla DoH canary) must never
+    // reach the upstream query; they always resolve as NXDOMAIN.
+    let opt_out = config::config().opt_out_domains.iter().any(|d| d.eq_ignore_ascii_case(name.trim_end_matches('.')));
+    if opt_out {
+        unsafe {
+            ptr::write(h_errnop, HOST_NOT_FOUND);
+        }
+        return (None, NSS_STATUS_NOTFOUND);
+    }
     let mut dns_name = match Name::from_str(name) {
         Ok(name) => name,
         Err(_) => return (None, NSS_STATUS_UNAVAIL),
@@ -199,27 +460,124 @@ fn retrieve_addrs(name: &str, errnop: *mut c_int, h_errnop: *mut c_int) -> (Opti
     if !dns_name.is_fqdn() {
         dns_name = dns_name.append_name(&Name::root());
     }
-    let resp = match resolve(&dns_name) {
+    if let Some(answer) = cache::get(&dns_name, record_type) {
+        return resolve_family(&dns_name, record_type, Some(answer), None, errnop, h_errnop);
+    }
+    let resp = resolve(&dns_name, record_type);
+    resolve_family(&dns_name, record_type, None, Some(resp), errnop, h_errnop)
+}
+
+/// Resolves A and AAAA for `name` at once, querying upstream concurrently
+/// for whichever families aren't already cached (the common case, since
+/// `_nss_doh_gethostbyname4_r` is family-agnostic and always wants both).
+fn retrieve_addrs_both(
+    name: &str,
+    errnop: *mut c_int,
+    h_errnop: *mut c_int,
+) -> ((Option<Vec<IpAddr>>, c_int), (Option<Vec<IpAddr>>, c_int)) {
+    unsafe {
+        ptr::write(errnop, libc::ENOENT);
+        ptr::write(h_errnop, NETDB_INTERNAL);
+    }
+    let opt_out = config::config().opt_out_domains.iter().any(|d| d.eq_ignore_ascii_case(name.trim_end_matches('.')));
+    if opt_out {
+        unsafe {
+            ptr::write(h_errnop, HOST_NOT_FOUND);
+        }
+        let not_found = (None, NSS_STATUS_NOTFOUND);
+        return (not_found.clone(), not_found);
+    }
+    let mut dns_name = match Name::from_str(name) {
+        Ok(name) => name,
+        Err(_) => return ((None, NSS_STATUS_UNAVAIL), (None, NSS_STATUS_UNAVAIL)),
+    };
+    if !dns_name.is_fqdn() {
+        dns_name = dns_name.append_name(&Name::root());
+    }
+
+    let v4_cached = cache::get(&dns_name, RecordType::A);
+    let v6_cached = cache::get(&dns_name, RecordType::AAAA);
+    let (v4_resp, v6_resp) = match (&v4_cached, &v6_cached) {
+        (Some(_), Some(_)) => (None, None),
+        (None, Some(_)) => (Some(resolve(&dns_name, RecordType::A)), None),
+        (Some(_), None) => (None, Some(resolve(&dns_name, RecordType::AAAA))),
+        (None, None) => {
+            let (a, aaaa) = runtime::query_both(config::config(), dns_name.clone());
+            (Some(a), Some(aaaa))
+        },
+    };
+
+    let v4 = resolve_family(&dns_name, RecordType::A, v4_cached, v4_resp, errnop, h_errnop);
+    let v6 = resolve_family(&dns_name, RecordType::AAAA, v6_cached, v6_resp, errnop, h_errnop);
+    (v4, v6)
+}
+
+// Shared tail of both the single-family and dual-family lookups: either
+// serves a cached answer, or turns a (possibly timed-out) upstream
+// response into the same `(addrs, status)` shape the NSS entry points want.
+fn resolve_family(
+    dns_name: &Name,
+    record_type: RecordType,
+    cached: Option<cache::Answer>,
+    resp: Option<Result<DnsResponse, runtime::QueryError>>,
+    errnop: *mut c_int,
+    h_errnop: *mut c_int,
+) -> (Option<Vec<IpAddr>>, c_int) {
+    if let Some(answer) = cached {
+        return match answer {
+            cache::Answer::Addrs(addrs) => {
+                unsafe {
+                    ptr::write(h_errnop, NETDB_SUCCESS);
+                }
+                (Some(addrs), NSS_STATUS_SUCCESS)
+            },
+            cache::Answer::NxDomain => {
+                unsafe {
+                    ptr::write(h_errnop, HOST_NOT_FOUND);
+                }
+                (None, NSS_STATUS_NOTFOUND)
+            },
+            cache::Answer::NoData | cache::Answer::Names(_) => {
+                unsafe {
+                    ptr::write(h_errnop, NO_DATA);
+                }
+                (None, NSS_STATUS_NOTFOUND)
+            },
+        };
+    }
+    let resp = match resp.expect("a family with no cached answer must have been queried") {
         Ok(resp) => resp,
-        Err(_) => return (None, NSS_STATUS_UNAVAIL),
+        Err(runtime::QueryError::Timeout) => {
+            unsafe {
+                ptr::write(errnop, libc::ETIMEDOUT);
+                ptr::write(h_errnop, TRY_AGAIN);
+            }
+            return (None, NSS_STATUS_TRYAGAIN);
+        },
+        Err(runtime::QueryError::Upstream(_)) => return (None, NSS_STATUS_UNAVAIL),
     };
     let msg = match resp.messages().nth(0) {
         Some(msg) => msg,
         None => return (None, NSS_STATUS_UNAVAIL),
     };
+    process_addrs_message(dns_name, record_type, msg, errnop, h_errnop)
+}
+
+fn process_addrs_message(
+    dns_name: &Name,
+    record_type: RecordType,
+    msg: &Message,
+    errnop: *mut c_int,
+    h_errnop: *mut c_int,
+) -> (Option<Vec<IpAddr>>, c_int) {
     let ans = msg.answers();
     unsafe {
         ptr::write(errnop, 0);
         ptr::write(h_errnop, NO_DATA);
     }
-    match msg.response_code() {
-        ResponseCode::NoError => (),
-        ResponseCode::NXDomain => {
-            unsafe {
-                ptr::write(h_errnop, HOST_NOT_FOUND);
-            }
-            return (None, NSS_STATUS_NOTFOUND);
-        },
+    let is_nxdomain = match msg.response_code() {
+        ResponseCode::NoError => false,
+        ResponseCode::NXDomain => true,
         ResponseCode::ServFail => {
             unsafe {
                 ptr::write(h_errnop, TRY_AGAIN);
@@ -232,16 +590,31 @@ fn retrieve_addrs(name: &str, errnop: *mut c_int, h_errnop: *mut c_int) -> (Opti
             }
             return (None, NSS_STATUS_UNAVAIL);
         },
+    };
+    let mut fetch = UpstreamFetch;
+    if let dnssec::Validation::Bogus = dnssec::validate(config::config(), dns_name, record_type, msg, &mut fetch) {
+        unsafe {
+            ptr::write(h_errnop, NO_RECOVERY);
+        }
+        return (None, NSS_STATUS_UNAVAIL);
+    }
+    if is_nxdomain {
+        unsafe {
+            ptr::write(h_errnop, HOST_NOT_FOUND);
+        }
+        cache::insert(dns_name.clone(), record_type, cache::Answer::NxDomain, ans);
+        return (None, NSS_STATUS_NOTFOUND);
     }
-    let mut looking_for = &dns_name;
-    let mut addrs = vec![];
+    let mut looking_for = dns_name;
+    let mut addrs: Vec<IpAddr> = vec![];
     let mut cnames = HashSet::new();
     'outer: loop {
+        let mut followed_cname = false;
         for record in ans.iter() {
             if record.name() == looking_for {
                 match record.rr_type() {
                     RecordType::CNAME => {
-                        // We must have no CNAME and A records with the same name
+                        // We must have no CNAME and A/AAAA records with the same name
                         if !addrs.is_empty() {
                             addrs.clear();
                             break 'outer;
@@ -255,41 +628,44 @@ fn retrieve_addrs(name: &str, errnop: *mut c_int, h_errnop: *mut c_int) -> (Opti
                         if cnames.contains(looking_for) {
                             break 'outer;
                         }
+                        followed_cname = true;
                         break;
                     },
-                    RecordType::A => {
+                    RecordType::A if record_type == RecordType::A => {
                         let addr = match record.rdata() {
                             RData::A(ref addr) => addr,
                             _ => panic!("bogus record data"),
                         };
-                        addrs.push(addr.clone());
+                        addrs.push(IpAddr::V4(*addr));
+                    },
+                    RecordType::AAAA if record_type == RecordType::AAAA => {
+                        let addr = match record.rdata() {
+                            RData::AAAA(ref addr) => addr,
+                            _ => panic!("bogus record data"),
+                        };
+                        addrs.push(IpAddr::V6(*addr));
                     },
                     _ => (),
                 }
             }
-        } 
-        if !addrs.is_empty() {
+        }
+        if !addrs.is_empty() || !followed_cname {
+            // Either we found what we're looking for, or this pass over an
+            // unchanged answer set produced nothing new (ordinary NODATA) --
+            // relooping wouldn't change the outcome, so stop here.
             break;
         }
     }
     if addrs.is_empty() {
+        cache::insert(dns_name.clone(), record_type, cache::Answer::NoData, ans);
         return (None, NSS_STATUS_NOTFOUND);
     }
+    cache::insert(dns_name.clone(), record_type, cache::Answer::Addrs(addrs.clone()), ans);
     (Some(addrs), NSS_STATUS_SUCCESS)
 }
 
-fn resolve(dns_name: &Name) -> Result<DnsResponse, failure::Error> {
-    let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 443);
-    let mut conn = HttpsClientConnection::new();
-    conn.add_ca(Certificate(Vec::from(
-        &include_bytes!("../DigiCertGlobalRootCA.crt")[..],
-    )));
-    let conn = conn.build(socket, String::from("cloudflare-dns.com"));
-    let client = SyncClient::new(conn);
-    let resp = client.query(
-        dns_name,
-        DNSClass::IN,
-        RecordType::A,
-    )?;
-    Ok(resp)
+/// Issues a single query against the shared, lazily-connected runtime
+/// (see `runtime.rs`), bounded by the configured per-query timeout.
+fn resolve(dns_name: &Name, record_type: RecordType) -> Result<DnsResponse, runtime::QueryError> {
+    runtime::query(config::config(), dns_name.clone(), record_type)
 }
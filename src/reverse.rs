@@ -0,0 +1,31 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use trust_dns::rr::Name;
+
+/// Builds the `in-addr.arpa.`/`ip6.arpa.` PTR query name for `addr`, per
+/// RFC 1035 section 3.5 (IPv4) and RFC 3596 section 2.5 (IPv6 nibbles).
+pub fn arpa_name(addr: &IpAddr) -> Name {
+    match addr {
+        IpAddr::V4(v4) => arpa_name_v4(v4),
+        IpAddr::V6(v6) => arpa_name_v6(v6),
+    }
+}
+
+fn arpa_name_v4(addr: &Ipv4Addr) -> Name {
+    let octets = addr.octets();
+    let text = format!(
+        "{}.{}.{}.{}.in-addr.arpa.",
+        octets[3], octets[2], octets[1], octets[0]
+    );
+    Name::from_str(&text).expect("well-formed in-addr.arpa name")
+}
+
+fn arpa_name_v6(addr: &Ipv6Addr) -> Name {
+    let mut nibbles = String::with_capacity(32 * 2);
+    for byte in addr.octets().iter().rev() {
+        nibbles.push_str(&format!("{:x}.{:x}.", byte & 0xf, byte >> 4));
+    }
+    let text = format!("{}ip6.arpa.", nibbles);
+    Name::from_str(&text).expect("well-formed ip6.arpa name")
+}
@@ -0,0 +1,141 @@
+use std::sync::Mutex;
+
+use once_cell::sync::OnceCell;
+use tokio::runtime::{Builder, Runtime};
+use trust_dns::client::{AsyncClient, ClientHandle};
+use trust_dns::https::HttpsClientStreamBuilder;
+use trust_dns::op::{DnsResponse, Edns, Message, Query};
+use trust_dns::rr::{DNSClass, Name, RecordType};
+
+use crate::config::Config;
+use crate::dnssec;
+
+/// Outcome of a single bounded upstream query.
+pub enum QueryError {
+    /// The query didn't complete within `Config::query_timeout`.
+    Timeout,
+    /// The transport or the upstream itself reported a failure.
+    Upstream(failure::Error),
+}
+
+impl QueryError {
+    // `query_both` needs to report the same failed connection attempt on
+    // both the A and AAAA branches, but `failure::Error` isn't `Clone`.
+    fn duplicate(&self) -> QueryError {
+        match self {
+            QueryError::Timeout => QueryError::Timeout,
+            QueryError::Upstream(e) => QueryError::Upstream(failure::format_err!("{}", e)),
+        }
+    }
+}
+
+// A single-threaded runtime is enough: NSS entry points are called one
+// at a time per process, and `AsyncClient` multiplexes queries over one
+// already-negotiated connection, so there's no parallelism to exploit
+// beyond awaiting the handful of futures in flight per lookup.
+static RUNTIME: OnceCell<Runtime> = OnceCell::new();
+static CLIENT: OnceCell<Mutex<AsyncClient>> = OnceCell::new();
+
+fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to start the resolver's background runtime")
+    })
+}
+
+// Bounded by `cfg.query_timeout`, same as any other upstream round trip:
+// a hung or refused connection must surface as `QueryError`, not a panic
+// that unwinds through the `extern "C"` NSS boundary into the caller.
+async fn connect(cfg: &Config) -> Result<AsyncClient, QueryError> {
+    let mut builder = HttpsClientStreamBuilder::new();
+    for ca in &cfg.ca_certs {
+        builder.add_ca(ca.clone());
+    }
+    let (stream, handle) = builder.build(cfg.upstream, cfg.sni.clone());
+    let (client, bg) = to_result(tokio::time::timeout(cfg.query_timeout, AsyncClient::connect(stream, handle)).await)?;
+    tokio::spawn(bg);
+    Ok(client)
+}
+
+// Reused across calls: this is the whole point of moving off SyncClient,
+// which re-did the TLS handshake on every single lookup.
+fn client(cfg: &Config) -> Result<AsyncClient, QueryError> {
+    let cell = CLIENT.get_or_try_init(|| runtime().block_on(connect(cfg)).map(Mutex::new))?;
+    Ok(cell.lock().unwrap().clone())
+}
+
+fn dnssec_message(name: Name, record_type: RecordType) -> Message {
+    let mut query = Query::new();
+    query.set_name(name);
+    query.set_query_class(DNSClass::IN);
+    query.set_query_type(record_type);
+    let mut msg = Message::new();
+    msg.add_query(query);
+    msg.set_recursion_desired(true);
+    let mut edns = Edns::new();
+    dnssec::set_do_bit(&mut edns);
+    msg.set_edns(edns);
+    msg
+}
+
+/// Issues a single query against the configured upstream, bounded by
+/// `cfg.query_timeout`.
+pub fn query(cfg: &Config, name: Name, record_type: RecordType) -> Result<DnsResponse, QueryError> {
+    let mut client = client(cfg)?;
+    runtime().block_on(async move {
+        let fut = if cfg.dnssec {
+            client.send(dnssec_message(name, record_type))
+        } else {
+            client.query(name, DNSClass::IN, record_type)
+        };
+        to_result(tokio::time::timeout(cfg.query_timeout, fut).await)
+    })
+}
+
+/// Issues A and AAAA concurrently for `name`, each bounded by
+/// `cfg.query_timeout`, and returns both outcomes independently so a
+/// failed or timed-out AAAA doesn't cost the A answer or vice versa. The
+/// call returns once both queries have finished or timed out.
+pub fn query_both(
+    cfg: &Config,
+    name: Name,
+) -> (Result<DnsResponse, QueryError>, Result<DnsResponse, QueryError>) {
+    let mut client_a = match client(cfg) {
+        Ok(client) => client,
+        Err(e) => {
+            let dup = e.duplicate();
+            return (Err(e), Err(dup));
+        },
+    };
+    let mut client_aaaa = client_a.clone();
+    let name_aaaa = name.clone();
+    runtime().block_on(async move {
+        let fut_a = if cfg.dnssec {
+            client_a.send(dnssec_message(name, RecordType::A))
+        } else {
+            client_a.query(name, DNSClass::IN, RecordType::A)
+        };
+        let fut_aaaa = if cfg.dnssec {
+            client_aaaa.send(dnssec_message(name_aaaa, RecordType::AAAA))
+        } else {
+            client_aaaa.query(name_aaaa, DNSClass::IN, RecordType::AAAA)
+        };
+        let (a, aaaa) = tokio::join!(
+            tokio::time::timeout(cfg.query_timeout, fut_a),
+            tokio::time::timeout(cfg.query_timeout, fut_aaaa)
+        );
+        (to_result(a), to_result(aaaa))
+    })
+}
+
+fn to_result<T>(
+    outcome: Result<Result<T, trust_dns::error::ClientError>, tokio::time::error::Elapsed>,
+) -> Result<T, QueryError> {
+    match outcome {
+        Ok(Ok(resp)) => Ok(resp),
+        Ok(Err(e)) => Err(QueryError::Upstream(e.into())),
+        Err(_) => Err(QueryError::Timeout),
+    }
+}